@@ -1,9 +1,12 @@
 use bevy::{
-    core::{Pod, Zeroable},
-    ecs::bundle::Bundle,
+    asset::Handle,
+    ecs::{bundle::Bundle, component::Component},
     math::{Vec3, Vec4},
-    prelude::{Color, GlobalTransform, Transform},
+    prelude::{GlobalTransform, Transform},
 };
+use crevice::std140::AsStd140;
+
+use crate::draped_line_style::DrapedLineStyle;
 
 #[derive(Bundle, Debug, Default)]
 pub struct DrapedLineBundle {
@@ -12,13 +15,18 @@ pub struct DrapedLineBundle {
     pub global_transform: GlobalTransform,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Component, Clone, Debug)]
 pub struct DrapedLine {
     pub point0: Vec3,
     pub point1: Vec3,
-    pub width: f32,
-    pub color: Color,
     pub plane_dir: Vec3,
+    pub drape_mode: DrapeMode,
+    /// Offset applied along the surface normal when draping onto the depth
+    /// prepass, to avoid z-fighting with the terrain itself.
+    pub depth_bias: f32,
+    /// Width, color and dash pattern live on the shared [`DrapedLineStyle`]
+    /// asset instead, so many lines can reuse one style.
+    pub style: Handle<DrapedLineStyle>,
 }
 
 impl Default for DrapedLine {
@@ -26,19 +34,48 @@ impl Default for DrapedLine {
         DrapedLine {
             point0: Default::default(),
             point1: Default::default(),
-            width: 1.0,
-            color: Color::WHITE,
             plane_dir: -Vec3::Y,
+            drape_mode: DrapeMode::default(),
+            depth_bias: 0.02,
+            style: Default::default(),
         }
     }
 }
 
-#[repr(C)]
-#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+/// How a [`DrapedLine`] conforms to the ground beneath it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DrapeMode {
+    /// Project the segment onto the flat plane through `plane_dir`. Cheap,
+    /// and correct as long as the ground under the line is actually flat.
+    Plane,
+    /// Reconstruct world position from the terrain depth prepass along the
+    /// projection ray, so the line conforms to uneven ground.
+    Depth,
+}
+
+impl Default for DrapeMode {
+    fn default() -> Self {
+        DrapeMode::Plane
+    }
+}
+
+/// GPU-side mirror of a [`DrapedLine`] and its resolved [`DrapedLineStyle`],
+/// laid out by crevice so the std140 alignment rules are derived rather than
+/// hand-padded. It is uploaded as per-instance vertex data, and the pipeline's
+/// vertex attributes look up each field's offset in this layout, so reordering
+/// fields can't silently desync them; a new field still needs its own
+/// attribute in `draped_line_vertex_attributes`.
+#[derive(Copy, Clone, Debug, AsStd140)]
 pub struct DrapedLineUniform {
-    pub point0: Vec4,    // padding
-    pub point1: Vec4,    // padding
-    pub width: [f32; 4], // padding
+    pub point0: Vec3,
+    pub point1: Vec3,
+    pub width: f32,
     pub color: Vec4,
-    pub plane_dir: Vec4, // padding
+    pub plane_dir: Vec3,
+    /// 0.0 for [`DrapeMode::Plane`], 1.0 for [`DrapeMode::Depth`] — std140 has
+    /// no bool, so the fragment shader branches on this instead.
+    pub drape_mode: f32,
+    pub depth_bias: f32,
+    pub dash_length: f32,
+    pub gap_length: f32,
 }
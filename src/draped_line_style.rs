@@ -0,0 +1,146 @@
+use bevy::{
+    asset::HandleId,
+    ecs::prelude::*,
+    math::Vec4,
+    prelude::{Assets, Color, Time},
+    reflect::TypeUuid,
+};
+use interpolation::{Ease, EaseFunction};
+
+use crate::draped_line::DrapedLine;
+
+/// The width, color and dash pattern shared by every [`DrapedLine`](crate::draped_line::DrapedLine)
+/// that references this asset via a `Handle<DrapedLineStyle>`.
+#[derive(Debug, Clone, TypeUuid)]
+#[uuid = "9c4a2f1e-6f2a-4b1e-8b0a-4b8a7b6b7a11"]
+pub struct DrapedLineStyle {
+    pub width: f32,
+    pub color: Color,
+    pub dash_pattern: DashPattern,
+}
+
+/// `DrapedLine::style` defaults to `Handle::<DrapedLineStyle>::default()` (so
+/// a `DrapedLineBundle { draped_line: DrapedLine { point0, point1, .. }, .. }`
+/// built from `..Default::default()` has something to point at), but nothing
+/// registers an asset under that handle id on its own. Run this once at
+/// startup so that default handle actually resolves instead of
+/// `extract_draped_lines` silently dropping every line that never had its
+/// style set.
+pub fn register_default_draped_line_style(mut styles: ResMut<Assets<DrapedLineStyle>>) {
+    styles.set_untracked(
+        HandleId::default::<DrapedLineStyle>(),
+        DrapedLineStyle::default(),
+    );
+}
+
+impl Default for DrapedLineStyle {
+    fn default() -> Self {
+        DrapedLineStyle {
+            width: 1.0,
+            color: Color::WHITE,
+            dash_pattern: DashPattern::default(),
+        }
+    }
+}
+
+/// A dash/gap length pair, in world units. A zero `dash_length` means solid.
+#[derive(Debug, Clone, Copy)]
+pub struct DashPattern {
+    pub dash_length: f32,
+    pub gap_length: f32,
+}
+
+impl Default for DashPattern {
+    fn default() -> Self {
+        DashPattern {
+            dash_length: 0.0,
+            gap_length: 0.0,
+        }
+    }
+}
+
+/// A single endpoint of a [`DrapedLineStyleAnimation`] tween.
+#[derive(Debug, Clone, Copy)]
+pub struct DrapedLineStyleKeyframe {
+    pub width: f32,
+    pub color: Color,
+}
+
+/// How a [`DrapedLineStyleAnimation`] repeats once it reaches `end`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnimationLoopMode {
+    /// Jump back to `start` and play forward again.
+    Loop,
+    /// Play backward to `start`, then forward again.
+    PingPong,
+}
+
+/// Drives a [`DrapedLineStyle`]'s `width` and `color` between two keyframes
+/// over time, e.g. to pulse the width or lerp the color of a highlighted
+/// route. Attach to an entity that also has a [`DrapedLine`];
+/// `animate_draped_line_styles` resolves `DrapedLine::style` itself and
+/// writes the eased value into the style asset every frame.
+#[derive(Component, Debug, Clone)]
+pub struct DrapedLineStyleAnimation {
+    pub start: DrapedLineStyleKeyframe,
+    pub end: DrapedLineStyleKeyframe,
+    pub duration: f32,
+    pub easing: EaseFunction,
+    pub loop_mode: AnimationLoopMode,
+    elapsed: f32,
+}
+
+impl DrapedLineStyleAnimation {
+    pub fn new(
+        start: DrapedLineStyleKeyframe,
+        end: DrapedLineStyleKeyframe,
+        duration: f32,
+        easing: EaseFunction,
+        loop_mode: AnimationLoopMode,
+    ) -> Self {
+        DrapedLineStyleAnimation {
+            start,
+            end,
+            duration,
+            easing,
+            loop_mode,
+            elapsed: 0.0,
+        }
+    }
+}
+
+pub fn animate_draped_line_styles(
+    time: Res<Time>,
+    mut styles: ResMut<Assets<DrapedLineStyle>>,
+    mut animations: Query<(&mut DrapedLineStyleAnimation, &DrapedLine)>,
+) {
+    for (mut animation, draped_line) in animations.iter_mut() {
+        animation.elapsed += time.delta_seconds();
+
+        let phase = animation.elapsed / animation.duration;
+        let t = match animation.loop_mode {
+            AnimationLoopMode::Loop => phase.rem_euclid(1.0),
+            AnimationLoopMode::PingPong => {
+                let cycle = phase.rem_euclid(2.0);
+                if cycle <= 1.0 {
+                    cycle
+                } else {
+                    2.0 - cycle
+                }
+            }
+        };
+        let eased = t.calc(animation.easing);
+
+        if let Some(style) = styles.get_mut(&draped_line.style) {
+            style.width = lerp(animation.start.width, animation.end.width, eased);
+            style.color = Color::from(Vec4::from(animation.start.color).lerp(
+                Vec4::from(animation.end.color),
+                eased,
+            ));
+        }
+    }
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
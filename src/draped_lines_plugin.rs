@@ -0,0 +1,478 @@
+use bevy::asset::AssetServer;
+use bevy::core_pipeline::Transparent3d;
+use bevy::ecs::prelude::*;
+use bevy::ecs::system::lifetimeless::SRes;
+use bevy::log;
+use bevy::math::{Vec3, Vec4};
+use bevy::prelude::{Assets, Color, GlobalTransform, Plugin};
+use bevy::render::{
+    render_phase::{
+        DrawFunctions, RenderCommand, RenderCommandResult, RenderPhase, SetItemPipeline,
+        TrackedRenderPass,
+    },
+    render_resource::{
+        BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+        BindGroupLayoutEntry, BindingResource, BindingType, BlendState, Buffer, BufferDescriptor,
+        BufferUsages, CachedRenderPipelineId, ColorTargetState, ColorWrites, CompareFunction,
+        DepthBiasState, DepthStencilState, FragmentState, MultisampleState, PipelineCache,
+        PrimitiveState, RenderPipelineDescriptor, SamplerBindingType,
+        ShaderStages as BindingShaderStages, StencilState, TextureFormat, TextureSampleType,
+        TextureViewDimension, VertexAttribute, VertexBufferLayout, VertexFormat, VertexState,
+        VertexStepMode,
+    },
+    renderer::{RenderDevice, RenderQueue},
+    texture::BevyDefault,
+    view::ExtractedView,
+    RenderApp, RenderStage,
+};
+use bevy::utils::FloatOrd;
+use crevice::std140::{AsStd140, Std140};
+
+use crate::draped_line::{DrapedLine, DrapeMode, DrapedLineUniform};
+use crate::draped_line_style::DrapedLineStyle;
+use crate::terrain_depth_prepass::TerrainDepthPrepassTexture;
+
+/// A render-world copy of a [`DrapedLine`], already transformed into world
+/// space, with its [`DrapedLineStyle`] resolved.
+///
+/// Extracted once per frame from the main world so that queueing and drawing
+/// never have to touch `GlobalTransform` or `Assets<DrapedLineStyle>` again.
+pub struct ExtractedDrapedLine {
+    pub point0: Vec3,
+    pub point1: Vec3,
+    pub width: f32,
+    pub color: Color,
+    pub dash_length: f32,
+    pub gap_length: f32,
+    pub plane_dir: Vec3,
+    pub drape_mode: DrapeMode,
+    pub depth_bias: f32,
+}
+
+/// All draped lines extracted this frame, in extraction order.
+#[derive(Default)]
+pub struct ExtractedDrapedLines {
+    pub lines: Vec<ExtractedDrapedLine>,
+}
+
+pub struct DrapedLinesPlugin;
+
+impl Plugin for DrapedLinesPlugin {
+    fn build(&self, app: &mut bevy::prelude::App) {
+        let render_app = match app.get_sub_app_mut(RenderApp) {
+            Ok(render_app) => render_app,
+            Err(_) => return,
+        };
+
+        render_app
+            .init_resource::<DrapedLineMeta>()
+            .init_resource::<DrapedLinePipeline>()
+            .add_render_command::<Transparent3d, DrawDrapedLine>()
+            .add_system_to_stage(RenderStage::Extract, extract_draped_lines)
+            .add_system_to_stage(RenderStage::Queue, queue_draped_lines);
+    }
+}
+
+fn extract_draped_lines(
+    mut commands: Commands,
+    draped_lines: bevy::render::Extract<Query<(&DrapedLine, &GlobalTransform)>>,
+    styles: bevy::render::Extract<Res<Assets<DrapedLineStyle>>>,
+) {
+    let lines = draped_lines
+        .iter()
+        .filter_map(|(draped_line, global_transform)| {
+            let style = match styles.get(&draped_line.style) {
+                Some(style) => style,
+                None => {
+                    // The default handle is registered by
+                    // register_default_draped_line_style at startup, so
+                    // reaching this means the handle points at an asset
+                    // that was never added or has since been dropped.
+                    log::warn!(
+                        "DrapedLine's style handle {:?} doesn't resolve to a DrapedLineStyle; skipping it",
+                        draped_line.style
+                    );
+                    return None;
+                }
+            };
+            let matrix = global_transform.compute_matrix();
+            Some(ExtractedDrapedLine {
+                point0: matrix.transform_point3(draped_line.point0),
+                point1: matrix.transform_point3(draped_line.point1),
+                width: style.width,
+                color: style.color,
+                dash_length: style.dash_pattern.dash_length,
+                gap_length: style.dash_pattern.gap_length,
+                plane_dir: draped_line.plane_dir,
+                drape_mode: draped_line.drape_mode,
+                depth_bias: draped_line.depth_bias,
+            })
+        })
+        .collect();
+    commands.insert_resource(ExtractedDrapedLines { lines });
+}
+
+/// Render-world resource owning the instance buffer that backs every draped
+/// line's [`DrapedLineUniform`]. Grows to fit the line count but never
+/// shrinks, so adding and removing lines at runtime doesn't thrash
+/// allocations.
+///
+/// Also owns the bind group over the terrain depth prepass texture that
+/// [`DrapeMode::Depth`] lines sample to conform to uneven ground.
+pub struct DrapedLineMeta {
+    instance_buffer: Option<Buffer>,
+    instance_capacity: usize,
+    instance_count: u32,
+    depth_bind_group_layout: BindGroupLayout,
+    depth_bind_group: Option<BindGroup>,
+    /// Carries the batched `Transparent3d` item into every view's phase.
+    /// `DrawDrapedLine`'s render commands never read anything off it, so
+    /// the same entity is reused frame after frame instead of spawning a
+    /// fresh one (which the render world never despawns on its own).
+    phase_item_entity: Entity,
+}
+
+impl FromWorld for DrapedLineMeta {
+    fn from_world(world: &mut bevy::prelude::World) -> Self {
+        let phase_item_entity = world.spawn().id();
+        let render_device = world.resource::<RenderDevice>();
+        let depth_bind_group_layout =
+            render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: Some("draped_line_depth_prepass_bind_group_layout"),
+                entries: &[
+                    BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: BindingShaderStages::FRAGMENT,
+                        ty: BindingType::Texture {
+                            sample_type: TextureSampleType::Depth,
+                            view_dimension: TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: BindingShaderStages::FRAGMENT,
+                        ty: BindingType::Sampler(SamplerBindingType::NonFiltering),
+                        count: None,
+                    },
+                ],
+            });
+        DrapedLineMeta {
+            instance_buffer: None,
+            instance_capacity: 0,
+            instance_count: 0,
+            depth_bind_group_layout,
+            depth_bind_group: None,
+            phase_item_entity,
+        }
+    }
+}
+
+/// The one and only render pipeline every draped line is drawn with,
+/// specialized up front since nothing about a draped line varies the vertex
+/// layout or shader defs from one instance to the next.
+pub struct DrapedLinePipeline {
+    pipeline_id: CachedRenderPipelineId,
+}
+
+impl FromWorld for DrapedLinePipeline {
+    fn from_world(world: &mut bevy::prelude::World) -> Self {
+        let depth_bind_group_layout = world
+            .resource::<DrapedLineMeta>()
+            .depth_bind_group_layout
+            .clone();
+        let shader = world
+            .resource::<AssetServer>()
+            .load("shaders/draped_line.wgsl");
+
+        let attributes = draped_line_vertex_attributes();
+
+        let mut pipeline_cache = world.resource_mut::<PipelineCache>();
+        let pipeline_id = pipeline_cache.queue_render_pipeline(RenderPipelineDescriptor {
+            label: Some("draped_line_pipeline".into()),
+            layout: Some(vec![depth_bind_group_layout]),
+            vertex: VertexState {
+                shader: shader.clone(),
+                shader_defs: vec![],
+                entry_point: "vertex".into(),
+                buffers: vec![VertexBufferLayout {
+                    array_stride: DrapedLineUniform::std140_size_static() as u64,
+                    step_mode: VertexStepMode::Instance,
+                    attributes,
+                }],
+            },
+            fragment: Some(FragmentState {
+                shader,
+                shader_defs: vec![],
+                entry_point: "fragment".into(),
+                targets: vec![Some(ColorTargetState {
+                    format: TextureFormat::bevy_default(),
+                    blend: Some(BlendState::ALPHA_BLENDING),
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            primitive: PrimitiveState::default(),
+            depth_stencil: Some(DepthStencilState {
+                format: TextureFormat::Depth32Float,
+                // Draped lines are drawn as an overlay on top of whatever the
+                // main pass already resolved; they shouldn't fight it for the
+                // depth buffer.
+                depth_write_enabled: false,
+                depth_compare: CompareFunction::Always,
+                stencil: StencilState::default(),
+                bias: DepthBiasState::default(),
+            }),
+            multisample: MultisampleState::default(),
+        });
+
+        DrapedLinePipeline { pipeline_id }
+    }
+}
+
+/// Locates each of `DrapedLineUniform`'s fields within its real std140 layout
+/// by round-tripping a sentinel value through `as_std140()` and searching for
+/// each field's distinct value, instead of hand-typing an offset table that
+/// has to be kept in sync with crevice by eye (and silently desyncs the
+/// moment a field is added or reordered).
+fn draped_line_vertex_attributes() -> Vec<VertexAttribute> {
+    fn offset_of(bytes: &[u8], needle: f32) -> u64 {
+        let needle_bytes = needle.to_le_bytes();
+        bytes
+            .windows(4)
+            .position(|window| window == needle_bytes)
+            .expect("sentinel value missing from serialized DrapedLineUniform") as u64
+    }
+
+    // Every field gets its own value so none of them can be confused with
+    // one another while searching the serialized bytes.
+    let sentinel = DrapedLineUniform {
+        point0: Vec3::new(101.0, 102.0, 103.0),
+        point1: Vec3::new(104.0, 105.0, 106.0),
+        width: 107.0,
+        color: Vec4::new(108.0, 109.0, 110.0, 111.0),
+        plane_dir: Vec3::new(112.0, 113.0, 114.0),
+        drape_mode: 115.0,
+        depth_bias: 116.0,
+        dash_length: 117.0,
+        gap_length: 118.0,
+    }
+    .as_std140();
+    let bytes = sentinel.as_bytes();
+
+    vec![
+        VertexAttribute {
+            format: VertexFormat::Float32x3,
+            offset: offset_of(bytes, 101.0),
+            shader_location: 0,
+        },
+        VertexAttribute {
+            format: VertexFormat::Float32x3,
+            offset: offset_of(bytes, 104.0),
+            shader_location: 1,
+        },
+        VertexAttribute {
+            format: VertexFormat::Float32,
+            offset: offset_of(bytes, 107.0),
+            shader_location: 2,
+        },
+        VertexAttribute {
+            format: VertexFormat::Float32x4,
+            offset: offset_of(bytes, 108.0),
+            shader_location: 3,
+        },
+        VertexAttribute {
+            format: VertexFormat::Float32x3,
+            offset: offset_of(bytes, 112.0),
+            shader_location: 4,
+        },
+        VertexAttribute {
+            format: VertexFormat::Float32,
+            offset: offset_of(bytes, 115.0),
+            shader_location: 5,
+        },
+        VertexAttribute {
+            format: VertexFormat::Float32,
+            offset: offset_of(bytes, 116.0),
+            shader_location: 6,
+        },
+        VertexAttribute {
+            format: VertexFormat::Float32,
+            offset: offset_of(bytes, 117.0),
+            shader_location: 7,
+        },
+        VertexAttribute {
+            format: VertexFormat::Float32,
+            offset: offset_of(bytes, 118.0),
+            shader_location: 8,
+        },
+    ]
+}
+
+fn queue_draped_lines(
+    mut draped_line_meta: ResMut<DrapedLineMeta>,
+    draped_line_pipeline: Res<DrapedLinePipeline>,
+    extracted_draped_lines: Res<ExtractedDrapedLines>,
+    terrain_depth_prepass_texture: Option<Res<TerrainDepthPrepassTexture>>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    draw_functions: Res<DrawFunctions<Transparent3d>>,
+    mut views: Query<(&ExtractedView, &mut RenderPhase<Transparent3d>)>,
+) {
+    draped_line_meta.instance_count = 0;
+    if extracted_draped_lines.lines.is_empty() {
+        return;
+    }
+
+    // Farthest-from-camera first, so the single instanced draw below still
+    // blends overlapping translucent lines back-to-front.
+    let view_translation = views
+        .iter()
+        .next()
+        .map(|(view, _)| view.transform.translation())
+        .unwrap_or(Vec3::ZERO);
+    let mut lines: Vec<_> = extracted_draped_lines.lines.iter().collect();
+    lines.sort_by_key(|line| {
+        let midpoint = (line.point0 + line.point1) / 2.0;
+        std::cmp::Reverse(FloatOrd(view_translation.distance(midpoint)))
+    });
+
+    // The old fixed-layout buffer needed a DrapedLineCount header so the shader
+    // knew how many lines to loop over. Here every line gets its own instance
+    // at a fixed stride and draw() is told the instance count directly
+    // (see instance_count below), so there's nothing left for a header to carry.
+    let instance_size = DrapedLineUniform::std140_size_static();
+    let buffer_size = instance_size * lines.len();
+
+    if draped_line_meta.instance_capacity < lines.len() {
+        draped_line_meta.instance_buffer = Some(render_device.create_buffer(&BufferDescriptor {
+            label: Some("draped_line_instance_buffer"),
+            size: buffer_size as u64,
+            usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        }));
+        draped_line_meta.instance_capacity = lines.len();
+    }
+    let instance_buffer = draped_line_meta.instance_buffer.as_ref().unwrap();
+
+    let mut bytes = vec![0u8; buffer_size];
+    for (index, line) in lines.iter().enumerate() {
+        let instance = DrapedLineUniform {
+            point0: line.point0,
+            point1: line.point1,
+            width: line.width,
+            color: line.color.into(),
+            plane_dir: line.plane_dir,
+            drape_mode: match line.drape_mode {
+                DrapeMode::Plane => 0.0,
+                DrapeMode::Depth => 1.0,
+            },
+            depth_bias: line.depth_bias,
+            dash_length: line.dash_length,
+            gap_length: line.gap_length,
+        }
+        .as_std140();
+        let start = index * instance_size;
+        bytes[start..start + instance.as_bytes().len()].copy_from_slice(instance.as_bytes());
+    }
+    render_queue.write_buffer(instance_buffer, 0, &bytes);
+    draped_line_meta.instance_count = lines.len() as u32;
+
+    // Rebuilt whenever the prepass texture is (re)created, e.g. on resize.
+    if let Some(terrain_depth_prepass_texture) = &terrain_depth_prepass_texture {
+        draped_line_meta.depth_bind_group = Some(render_device.create_bind_group(
+            &BindGroupDescriptor {
+                label: Some("draped_line_depth_prepass_bind_group"),
+                layout: &draped_line_meta.depth_bind_group_layout,
+                entries: &[
+                    BindGroupEntry {
+                        binding: 0,
+                        resource: BindingResource::TextureView(
+                            &terrain_depth_prepass_texture.view,
+                        ),
+                    },
+                    BindGroupEntry {
+                        binding: 1,
+                        resource: BindingResource::Sampler(&terrain_depth_prepass_texture.sampler),
+                    },
+                ],
+            },
+        ));
+    }
+
+    let draw_draped_line = draw_functions.read().get_id::<DrawDrapedLine>().unwrap();
+
+    // `lines` is sorted farthest-first (see above), so the last entry is the
+    // nearest one to the camera. The whole batch is still a single phase item
+    // (chunk0-3's single instanced draw means there's no per-line item to sort),
+    // so this is only an approximation of real phase participation: it lets the
+    // batch interleave with other Transparent3d draws using its closest line,
+    // rather than always sorting as if it were at the camera itself.
+    let nearest_distance = lines
+        .last()
+        .map(|line| view_translation.distance((line.point0 + line.point1) / 2.0))
+        .unwrap_or(0.0);
+
+    for (_view, mut transparent_phase) in views.iter_mut() {
+        // One phase item stands in for the whole instanced draw; DrawDrapedLine
+        // doesn't read anything off it, so it's the same entity every frame
+        // (spawned once in DrapedLineMeta::from_world) rather than a fresh one
+        // the render world would otherwise never despawn.
+        transparent_phase.add(Transparent3d {
+            entity: draped_line_meta.phase_item_entity,
+            pipeline: draped_line_pipeline.pipeline_id,
+            draw_function: draw_draped_line,
+            distance: nearest_distance,
+        });
+    }
+}
+
+/// Binds the terrain depth prepass texture and the instance buffer, then
+/// issues one instanced draw covering every draped line, rather than a
+/// pipeline switch or draw call per line.
+pub type DrawDrapedLine = (
+    SetItemPipeline,
+    SetDrapedLineDepthBindGroup<0>,
+    DrawDrapedLineInstanced,
+);
+
+pub struct SetDrapedLineDepthBindGroup<const I: usize>;
+impl<const I: usize> RenderCommand<Transparent3d> for SetDrapedLineDepthBindGroup<I> {
+    type Param = SRes<DrapedLineMeta>;
+
+    fn render<'w>(
+        _view: Entity,
+        _item: Entity,
+        draped_line_meta: bevy::ecs::system::SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        // Lines using `DrapeMode::Plane` never sample this, so a missing
+        // prepass (e.g. no terrain in the scene) only breaks `Depth` mode.
+        match draped_line_meta.depth_bind_group.as_ref() {
+            Some(bind_group) => {
+                pass.set_bind_group(I, bind_group, &[]);
+                RenderCommandResult::Success
+            }
+            None => RenderCommandResult::Success,
+        }
+    }
+}
+
+pub struct DrawDrapedLineInstanced;
+impl RenderCommand<Transparent3d> for DrawDrapedLineInstanced {
+    type Param = SRes<DrapedLineMeta>;
+
+    fn render<'w>(
+        _view: Entity,
+        _item: Entity,
+        draped_line_meta: bevy::ecs::system::SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        if draped_line_meta.instance_count == 0 {
+            return RenderCommandResult::Failure;
+        }
+        pass.set_vertex_buffer(0, draped_line_meta.instance_buffer.as_ref().unwrap().slice(..));
+        pass.draw(0..6, 0..draped_line_meta.instance_count);
+        RenderCommandResult::Success
+    }
+}
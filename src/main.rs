@@ -1,35 +1,37 @@
 mod draped_line;
-mod draped_lines_node;
+mod draped_line_style;
+mod draped_lines_plugin;
+mod terrain_depth_prepass;
 use bevy::{prelude::*, render::camera::PerspectiveProjection};
 use bevy_4x_camera::{CameraRigBundle, FourXCameraPlugin};
 use bevy::{
     asset::LoadState,
     log,
+    pbr::{Material, MaterialMeshBundle, MaterialPipeline, MaterialPipelineKey, MaterialPlugin},
     prelude::*,
+    reflect::TypeUuid,
     render::{
-        pipeline::{PipelineDescriptor, RenderPipeline},
-        render_graph::{base, RenderGraph, RenderResourcesNode},
-        renderer::RenderResources,
-        shader::ShaderStages,
-        texture::AddressMode,
+        mesh::MeshVertexBufferLayout,
+        render_resource::{
+            AddressMode, AsBindGroup, RenderPipelineDescriptor, SamplerDescriptor, ShaderRef,
+            SpecializedMeshPipelineError,
+        },
+        texture::ImageSampler,
     },
 };
-use draped_lines_node::DrapedLinesNode;
-use node::DRAPED_LINES_NODE;
-
-use crate::draped_line::{DrapedLine, DrapedLineBundle};
+use draped_lines_plugin::DrapedLinesPlugin;
+use terrain_depth_prepass::TerrainDepthPrepassPlugin;
 
-mod uniform {
-    pub const DRAPED_LINES: &str = "DrapedLines";
-}
-// Names for new RenderGraph Nodes
-mod node {
-    pub const TERRAIN_MATERIAL_NODE: &str = "TerrainMaterial_node";
-    pub const DRAPED_LINES_NODE: &str = "DrapedLines_node";
-}
+use crate::draped_line::{DrapeMode, DrapedLine, DrapedLineBundle};
+use crate::draped_line_style::{
+    animate_draped_line_styles, register_default_draped_line_style, AnimationLoopMode,
+    DrapedLineStyle, DrapedLineStyleAnimation, DrapedLineStyleKeyframe,
+};
+use crate::terrain_depth_prepass::TerrainDepthPrepass;
+use interpolation::EaseFunction;
 
 // We need an AppState to track loading
-// This is required to modify the Texture::sampler, but we might as well use it to finish loading everything
+// This is required to modify the Image's sampler, but we might as well use it to finish loading everything
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 enum AppState {
     Setup,
@@ -41,6 +43,12 @@ fn main() {
 
     app.add_plugins(DefaultPlugins)
         .add_plugin(FourXCameraPlugin)
+        .add_plugin(MaterialPlugin::<TerrainMaterial>::default())
+        .add_plugin(DrapedLinesPlugin)
+        .add_plugin(TerrainDepthPrepassPlugin)
+        .add_asset::<DrapedLineStyle>()
+        .add_startup_system(register_default_draped_line_style.system())
+        .add_system(animate_draped_line_styles.system())
         // Adds the state
         .add_state(AppState::Setup)
         // and the state-dependent systems
@@ -51,27 +59,19 @@ fn main() {
             SystemSet::on_update(AppState::Setup).with_system(check_terrain_assets.system()),
         )
         .add_system_set(SystemSet::on_enter(AppState::Finished).with_system(setup.system()))
-        .add_startup_system(setup_render_graph.system())
         .run();
 }
 
 // Resources for tracking the loaded assets
 struct TerrainAssets {
     mesh: Handle<Mesh>,
-    texture: Handle<Texture>,
-    vs: Handle<Shader>,
-    fs: Handle<Shader>,
+    texture: Handle<Image>,
 }
 
 impl TerrainAssets {
     // Needed to be able to do a single get_group_load_state, can be done differently of course
     fn as_vec(&self) -> Vec<HandleUntyped> {
-        vec![
-            self.mesh.clone_untyped(),
-            self.texture.clone_untyped(),
-            self.vs.clone_untyped(),
-            self.fs.clone_untyped(),
-        ]
+        vec![self.mesh.clone_untyped(), self.texture.clone_untyped()]
     }
 }
 
@@ -82,8 +82,6 @@ fn load_terrain_assets(mut commands: Commands, asset_server: ResMut<AssetServer>
     let terrain_assets = TerrainAssets {
         mesh: asset_server.load("models/example_quarry2_simplified_3d_mesh.glb#Mesh0/Primitive0"),
         texture: asset_server.load("textures/terrain_LUT.png"),
-        vs: asset_server.load("shaders/terrain.vert"),
-        fs: asset_server.load("shaders/terrain.frag"),
     };
     commands.insert_resource(terrain_assets);
 }
@@ -106,79 +104,87 @@ fn check_terrain_assets(
     }
 }
 
-// TerrainMaterial is used by the terrain vertex shader to scale and offset the UVs
-// Currently not an Asset, but can easily be turned into one if it's desirable to reuse the
-// same material on multiple meshes
-#[derive(Debug, RenderResources)]
+// TerrainMaterial scales and offsets the UVs and samples the terrain LUT
+// texture itself, replacing StandardMaterial entirely instead of layering a
+// second fixed-pipeline node underneath it. One .wgsl file holds both the
+// vertex and fragment entry points, as draped_lines_plugin's shader does,
+// instead of the separate .vert/.frag pair the legacy RenderResourcesNode
+// setup used.
+#[derive(AsBindGroup, TypeUuid, Debug, Clone)]
+#[uuid = "7c9b8a2e-3f1d-4b6a-9e2c-1a8f4d6b9c3e"]
 struct TerrainMaterial {
+    #[uniform(0)]
     scale: f32,
+    #[uniform(0)]
     offset: f32,
+    #[texture(1)]
+    #[sampler(2)]
+    base_color_texture: Handle<Image>,
 }
 
-fn setup_render_graph(mut render_graph: ResMut<RenderGraph>) {
-    render_graph.add_system_node(
-        node::TERRAIN_MATERIAL_NODE,
-        RenderResourcesNode::<TerrainMaterial>::new(true),
-    );
-    render_graph
-        .add_node_edge(node::TERRAIN_MATERIAL_NODE, base::node::MAIN_PASS)
-        .unwrap();
-
-    render_graph.add_system_node(DRAPED_LINES_NODE, DrapedLinesNode::new(50));
-    render_graph
-        .add_node_edge(node::DRAPED_LINES_NODE, base::node::MAIN_PASS)
-        .unwrap();
+impl Material for TerrainMaterial {
+    fn vertex_shader() -> ShaderRef {
+        "shaders/terrain.wgsl".into()
+    }
+
+    fn fragment_shader() -> ShaderRef {
+        "shaders/terrain.wgsl".into()
+    }
+
+    fn specialize(
+        _pipeline: &MaterialPipeline<Self>,
+        descriptor: &mut RenderPipelineDescriptor,
+        _layout: &MeshVertexBufferLayout,
+        _key: MaterialPipelineKey<Self>,
+    ) -> Result<(), SpecializedMeshPipelineError> {
+        // The legacy pipeline disabled backface culling for the terrain mesh; keep that.
+        descriptor.primitive.cull_mode = None;
+        Ok(())
+    }
 }
 
 fn setup(
     mut commands: Commands,
-    mut pipelines: ResMut<Assets<PipelineDescriptor>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
-    mut textures: ResMut<Assets<Texture>>,
+    mut images: ResMut<Assets<Image>>,
+    mut terrain_materials: ResMut<Assets<TerrainMaterial>>,
+    mut draped_line_styles: ResMut<Assets<DrapedLineStyle>>,
     terrain_assets: Res<TerrainAssets>,
 ) {
-    // Create a new shader pipeline with a custom vertex shader loaded from the asset directory
-    // and the pbr fragment shader
-    let mut pipe = PipelineDescriptor::default_config(ShaderStages {
-        vertex: terrain_assets.vs.clone(),
-        fragment: Some(terrain_assets.fs.clone()),
+    let image = images.get_mut(&terrain_assets.texture).unwrap();
+    image.sampler_descriptor = ImageSampler::Descriptor(SamplerDescriptor {
+        address_mode_v: AddressMode::Repeat,
+        ..Default::default()
     });
-    pipe.primitive.cull_mode = None;
-    let pipeline_handle = pipelines.add(pipe);
-
-    let mut texture = textures.get_mut(terrain_assets.texture.clone()).unwrap();
-    texture.sampler.address_mode_v = AddressMode::Repeat;
 
-    let material = materials.add(StandardMaterial {
-        base_color: Color::WHITE,
-        base_color_texture: Some(terrain_assets.texture.clone()),
-        roughness: 1.0,
-        metallic: 0.0,
-        ..Default::default()
+    let material = terrain_materials.add(TerrainMaterial {
+        scale: 1.0 / 6.0,
+        offset: 0.0,
+        base_color_texture: terrain_assets.texture.clone(),
     });
 
     commands
-        .spawn_bundle(PbrBundle {
+        .spawn_bundle(MaterialMeshBundle {
             mesh: terrain_assets.mesh.clone(),
-            render_pipelines: RenderPipelines::from_pipelines(vec![RenderPipeline::new(
-                pipeline_handle,
-            )]),
-            transform: Transform::from_xyz(0.0, 0.0, 0.0),
             material,
+            transform: Transform::from_xyz(0.0, 0.0, 0.0),
             ..Default::default()
         })
-        .insert(TerrainMaterial {
-            scale: 1.0 / 6.0,
-            offset: 0.0,
-        });
+        .insert(TerrainDepthPrepass);
+
+    // The quarry boundary lines all share one style, so tweaking it restyles all four at once.
+    let boundary_style = draped_line_styles.add(DrapedLineStyle {
+        width: 1.0,
+        color: Color::RED,
+        ..Default::default()
+    });
 
     commands.spawn_bundle(DrapedLineBundle {
         draped_line: DrapedLine {
             point0: Vec3::new(200.0, 0.0, 100.0),
             point1: Vec3::new(200.0, 0.0, -100.0),
-            width: 1.0,
-            color: Color::RED,
             plane_dir: -Vec3::Y,
+            style: boundary_style.clone(),
+            ..Default::default()
         },
         ..Default::default()
     });
@@ -187,9 +193,9 @@ fn setup(
         draped_line: DrapedLine {
             point0: Vec3::new(-200.0, 0.0, -100.0),
             point1: Vec3::new(200.0, 0.0, -100.0),
-            width: 1.0,
-            color: Color::RED,
             plane_dir: -Vec3::Y,
+            style: boundary_style.clone(),
+            ..Default::default()
         },
         ..Default::default()
     });
@@ -198,9 +204,9 @@ fn setup(
         draped_line: DrapedLine {
             point0: Vec3::new(-200.0, 0.0, 100.0),
             point1: Vec3::new(200.0, 0.0, 100.0),
-            width: 1.0,
-            color: Color::RED,
             plane_dir: -Vec3::Y,
+            style: boundary_style.clone(),
+            ..Default::default()
         },
         ..Default::default()
     });
@@ -209,13 +215,45 @@ fn setup(
         draped_line: DrapedLine {
             point0: Vec3::new(-200.0, 0.0, 100.0),
             point1: Vec3::new(-200.0, 0.0, -100.0),
-            width: 1.0,
-            color: Color::RED,
             plane_dir: -Vec3::Y,
+            style: boundary_style,
+            ..Default::default()
         },
         ..Default::default()
     });
 
+    // This one follows the quarry's actual height instead of floating above it,
+    // and pulses to call it out as the highlighted route.
+    let highlighted_route_style = draped_line_styles.add(DrapedLineStyle {
+        width: 1.0,
+        color: Color::GREEN,
+        ..Default::default()
+    });
+    commands
+        .spawn_bundle(DrapedLineBundle {
+            draped_line: DrapedLine {
+                point0: Vec3::new(0.0, 0.0, 100.0),
+                point1: Vec3::new(0.0, 0.0, -100.0),
+                drape_mode: DrapeMode::Depth,
+                style: highlighted_route_style,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .insert(DrapedLineStyleAnimation::new(
+            DrapedLineStyleKeyframe {
+                width: 1.0,
+                color: Color::GREEN,
+            },
+            DrapedLineStyleKeyframe {
+                width: 4.0,
+                color: Color::YELLOW,
+            },
+            1.5,
+            EaseFunction::QuadraticInOut,
+            AnimationLoopMode::PingPong,
+        ));
+
     // light
     commands.spawn_bundle(PointLightBundle {
         point_light: PointLight {
@@ -0,0 +1,446 @@
+use bevy::core_pipeline::core_3d::{self, Camera3d};
+use bevy::ecs::prelude::*;
+use bevy::math::{Mat4, UVec2};
+use bevy::prelude::{GlobalTransform, Handle, Mesh, Plugin};
+use bevy::render::{
+    mesh::{GpuBufferInfo, MeshVertexBufferLayout},
+    render_asset::RenderAssets,
+    render_graph::{Node, NodeRunError, RenderGraph, RenderGraphContext, SlotInfo, SlotType},
+    render_resource::{
+        BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+        BindGroupLayoutEntry, BindingResource, BindingType, Buffer, BufferBindingType,
+        BufferDescriptor, BufferUsages, CachedRenderPipelineId, DepthBiasState, DepthStencilState,
+        Extent3d, LoadOp, MultisampleState, Operations, PipelineCache, PrimitiveState,
+        CompareFunction, RenderPassDepthStencilAttachment, RenderPassDescriptor,
+        RenderPipelineDescriptor, Sampler, SamplerDescriptor,
+        ShaderStages as BindingShaderStages, Shader, SpecializedMeshPipeline,
+        SpecializedMeshPipelineError, SpecializedMeshPipelines, StencilState, TextureDescriptor,
+        TextureDimension, TextureFormat, TextureUsages, TextureView, VertexState,
+    },
+    renderer::{RenderContext, RenderDevice, RenderQueue},
+    view::{ExtractedView, ViewUniformOffset, ViewUniforms},
+    Extract, RenderApp, RenderStage,
+};
+use bevy::asset::AssetServer;
+use crevice::std140::{AsStd140, Std140};
+
+/// Marks an entity's mesh to be rendered into the terrain depth prepass, so
+/// [`DrapeMode::Depth`](crate::draped_line::DrapeMode) lines can sample the
+/// real ground height instead of a flat plane.
+#[derive(Component, Default, Clone, Copy)]
+pub struct TerrainDepthPrepass;
+
+pub const TERRAIN_DEPTH_PREPASS_NODE: &str = "terrain_depth_prepass";
+
+pub struct TerrainDepthPrepassPlugin;
+
+impl Plugin for TerrainDepthPrepassPlugin {
+    fn build(&self, app: &mut bevy::prelude::App) {
+        let render_app = match app.get_sub_app_mut(RenderApp) {
+            Ok(render_app) => render_app,
+            Err(_) => return,
+        };
+
+        render_app
+            .init_resource::<TerrainDepthPrepassPipeline>()
+            .init_resource::<SpecializedMeshPipelines<TerrainDepthPrepassPipeline>>()
+            .init_resource::<TerrainDepthPrepassMeta>()
+            .add_system_to_stage(RenderStage::Extract, extract_terrain_depth_meshes)
+            .add_system_to_stage(RenderStage::Prepare, prepare_terrain_depth_prepass_texture)
+            .add_system_to_stage(RenderStage::Queue, queue_terrain_depth_prepass);
+
+        let mut render_graph = render_app.world.resource_mut::<RenderGraph>();
+        let core_3d_graph = render_graph
+            .get_sub_graph_mut(core_3d::graph::NAME)
+            .unwrap();
+        core_3d_graph.add_node(TERRAIN_DEPTH_PREPASS_NODE, TerrainDepthPrepassNode::new());
+        core_3d_graph
+            .add_node_edge(
+                TERRAIN_DEPTH_PREPASS_NODE,
+                core_3d::graph::node::MAIN_PASS,
+            )
+            .unwrap();
+        // Feeds the same view entity every other core_3d node gets, so `run`
+        // can look up that view's `ViewUniformOffset` instead of guessing.
+        core_3d_graph
+            .add_slot_edge(
+                core_3d_graph.input_node().id,
+                core_3d::graph::input::VIEW_ENTITY,
+                TERRAIN_DEPTH_PREPASS_NODE,
+                "view",
+            )
+            .unwrap();
+    }
+}
+
+/// Render-world copy of the terrain meshes marked with [`TerrainDepthPrepass`].
+#[derive(Default)]
+struct ExtractedTerrainDepthMeshes {
+    meshes: Vec<(Handle<Mesh>, GlobalTransform)>,
+}
+
+fn extract_terrain_depth_meshes(
+    mut commands: Commands,
+    query: Extract<Query<(&Handle<Mesh>, &GlobalTransform), With<TerrainDepthPrepass>>>,
+) {
+    let meshes = query
+        .iter()
+        .map(|(mesh, transform)| (mesh.clone_weak(), *transform))
+        .collect();
+    commands.insert_resource(ExtractedTerrainDepthMeshes { meshes });
+}
+
+/// Render-world resource holding the depth texture that the terrain is
+/// rendered into, and the sampler draped lines use to read it back.
+pub struct TerrainDepthPrepassTexture {
+    pub view: TextureView,
+    pub sampler: Sampler,
+    /// The viewport size the texture was created at, so
+    /// `prepare_terrain_depth_prepass_texture` only recreates it on resize
+    /// instead of every frame.
+    size: UVec2,
+}
+
+fn prepare_terrain_depth_prepass_texture(
+    mut commands: Commands,
+    render_device: Res<RenderDevice>,
+    views: Query<&ExtractedView, With<Camera3d>>,
+    terrain_depth_prepass_texture: Option<Res<TerrainDepthPrepassTexture>>,
+) {
+    let Some(view) = views.iter().next() else {
+        return;
+    };
+
+    let size = UVec2::new(view.viewport.z.max(1), view.viewport.w.max(1));
+    if let Some(terrain_depth_prepass_texture) = &terrain_depth_prepass_texture {
+        if terrain_depth_prepass_texture.size == size {
+            return;
+        }
+    }
+
+    let texture = render_device.create_texture(&TextureDescriptor {
+        label: Some("terrain_depth_prepass_texture"),
+        size: Extent3d {
+            width: size.x,
+            height: size.y,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: TextureDimension::D2,
+        format: TextureFormat::Depth32Float,
+        usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+    });
+    let sampler = render_device.create_sampler(&SamplerDescriptor {
+        label: Some("terrain_depth_prepass_sampler"),
+        ..Default::default()
+    });
+
+    commands.insert_resource(TerrainDepthPrepassTexture {
+        view: texture.create_view(&Default::default()),
+        sampler,
+        size,
+    });
+}
+
+/// Specializes the depth-only pipeline terrain meshes are rendered with.
+/// There's exactly one draw-time variant per distinct vertex buffer layout
+/// (terrain only ever has one mesh, but meshes loaded from glTF can still
+/// vary in whether they carry normals/UVs the depth pass doesn't use).
+pub struct TerrainDepthPrepassPipeline {
+    shader: Handle<Shader>,
+    view_bind_group_layout: BindGroupLayout,
+    mesh_bind_group_layout: BindGroupLayout,
+}
+
+impl FromWorld for TerrainDepthPrepassPipeline {
+    fn from_world(world: &mut bevy::prelude::World) -> Self {
+        let shader = world
+            .resource::<AssetServer>()
+            .load("shaders/terrain_depth_prepass.wgsl");
+        let render_device = world.resource::<RenderDevice>();
+        let view_bind_group_layout =
+            render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: Some("terrain_depth_prepass_view_bind_group_layout"),
+                entries: &[BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: BindingShaderStages::VERTEX,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+            });
+        let mesh_bind_group_layout =
+            render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: Some("terrain_depth_prepass_mesh_bind_group_layout"),
+                entries: &[BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: BindingShaderStages::VERTEX,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+            });
+        TerrainDepthPrepassPipeline {
+            shader,
+            view_bind_group_layout,
+            mesh_bind_group_layout,
+        }
+    }
+}
+
+impl SpecializedMeshPipeline for TerrainDepthPrepassPipeline {
+    type Key = ();
+
+    fn specialize(
+        &self,
+        _key: Self::Key,
+        layout: &MeshVertexBufferLayout,
+    ) -> Result<RenderPipelineDescriptor, SpecializedMeshPipelineError> {
+        let vertex_buffer_layout =
+            layout.get_layout(&[Mesh::ATTRIBUTE_POSITION.at_shader_location(0)])?;
+
+        Ok(RenderPipelineDescriptor {
+            label: Some("terrain_depth_prepass_pipeline".into()),
+            layout: Some(vec![
+                self.view_bind_group_layout.clone(),
+                self.mesh_bind_group_layout.clone(),
+            ]),
+            vertex: VertexState {
+                shader: self.shader.clone(),
+                shader_defs: vec![],
+                entry_point: "vertex".into(),
+                buffers: vec![vertex_buffer_layout],
+            },
+            fragment: None,
+            primitive: PrimitiveState::default(),
+            depth_stencil: Some(DepthStencilState {
+                format: TextureFormat::Depth32Float,
+                depth_write_enabled: true,
+                depth_compare: CompareFunction::Less,
+                stencil: StencilState::default(),
+                bias: DepthBiasState::default(),
+            }),
+            multisample: MultisampleState::default(),
+        })
+    }
+}
+
+/// Render-world resource owning the view and per-mesh-transform bind groups
+/// [`TerrainDepthPrepassNode`] needs to actually draw, plus the dynamic
+/// uniform buffer the per-mesh transforms are written into every frame.
+#[derive(Default)]
+pub struct TerrainDepthPrepassMeta {
+    mesh_uniform_buffer: Option<Buffer>,
+    mesh_uniform_capacity: usize,
+    view_bind_group: Option<BindGroup>,
+    mesh_bind_group: Option<BindGroup>,
+}
+
+/// GPU-side mirror of a terrain mesh's model matrix, one per dynamic-offset
+/// slot in `TerrainDepthPrepassMeta`'s mesh uniform buffer.
+#[derive(Copy, Clone, AsStd140)]
+struct TerrainMeshTransformUniform {
+    transform: Mat4,
+}
+
+/// One terrain mesh, specialized and ready for [`TerrainDepthPrepassNode`] to
+/// draw: which `GpuMesh` to pull vertex/index buffers from, which pipeline
+/// to bind, and the dynamic offset into the mesh transform uniform buffer.
+struct QueuedTerrainDepthMesh {
+    mesh: Handle<Mesh>,
+    pipeline_id: CachedRenderPipelineId,
+    mesh_uniform_offset: u32,
+}
+
+#[derive(Default)]
+struct QueuedTerrainDepthMeshes {
+    meshes: Vec<QueuedTerrainDepthMesh>,
+}
+
+#[allow(clippy::too_many_arguments)]
+fn queue_terrain_depth_prepass(
+    mut commands: Commands,
+    mut meta: ResMut<TerrainDepthPrepassMeta>,
+    pipeline: Res<TerrainDepthPrepassPipeline>,
+    mut pipeline_cache: ResMut<PipelineCache>,
+    mut specialized_pipelines: ResMut<SpecializedMeshPipelines<TerrainDepthPrepassPipeline>>,
+    extracted_meshes: Option<Res<ExtractedTerrainDepthMeshes>>,
+    gpu_meshes: Res<RenderAssets<Mesh>>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    view_uniforms: Res<ViewUniforms>,
+) {
+    let Some(extracted_meshes) = extracted_meshes else {
+        return;
+    };
+    let Some(view_binding) = view_uniforms.uniforms.binding() else {
+        return;
+    };
+
+    // Align each mesh's slot to the device's minimum dynamic-offset
+    // alignment so `set_bind_group` offsets are valid.
+    let alignment = render_device.limits().min_uniform_buffer_offset_alignment as u64;
+    let stride =
+        (TerrainMeshTransformUniform::std140_size_static() as u64).max(alignment);
+
+    let mesh_count = extracted_meshes.meshes.len().max(1);
+    let buffer_size = stride * mesh_count as u64;
+    if meta.mesh_uniform_capacity < mesh_count {
+        meta.mesh_uniform_buffer = Some(render_device.create_buffer(&BufferDescriptor {
+            label: Some("terrain_depth_prepass_mesh_uniform_buffer"),
+            size: buffer_size,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        }));
+        meta.mesh_uniform_capacity = mesh_count;
+    }
+    let mesh_uniform_buffer = meta.mesh_uniform_buffer.as_ref().unwrap();
+
+    meta.view_bind_group = Some(render_device.create_bind_group(&BindGroupDescriptor {
+        label: Some("terrain_depth_prepass_view_bind_group"),
+        layout: &pipeline.view_bind_group_layout,
+        entries: &[BindGroupEntry {
+            binding: 0,
+            resource: view_binding,
+        }],
+    }));
+    meta.mesh_bind_group = Some(render_device.create_bind_group(&BindGroupDescriptor {
+        label: Some("terrain_depth_prepass_mesh_bind_group"),
+        layout: &pipeline.mesh_bind_group_layout,
+        entries: &[BindGroupEntry {
+            binding: 0,
+            resource: mesh_uniform_buffer.as_entire_binding(),
+        }],
+    }));
+
+    let mut queued = Vec::with_capacity(extracted_meshes.meshes.len());
+    for (index, (mesh_handle, transform)) in extracted_meshes.meshes.iter().enumerate() {
+        let Some(gpu_mesh) = gpu_meshes.get(mesh_handle) else {
+            continue;
+        };
+        let Ok(pipeline_id) = specialized_pipelines.specialize(
+            &mut pipeline_cache,
+            &pipeline,
+            (),
+            &gpu_mesh.layout,
+        ) else {
+            continue;
+        };
+
+        let offset = index as u64 * stride;
+        let uniform = TerrainMeshTransformUniform {
+            transform: transform.compute_matrix(),
+        }
+        .as_std140();
+        render_queue.write_buffer(mesh_uniform_buffer, offset, uniform.as_bytes());
+
+        queued.push(QueuedTerrainDepthMesh {
+            mesh: mesh_handle.clone_weak(),
+            pipeline_id,
+            mesh_uniform_offset: offset as u32,
+        });
+    }
+    commands.insert_resource(QueuedTerrainDepthMeshes { meshes: queued });
+}
+
+/// Renders every mesh marked [`TerrainDepthPrepass`] into the depth texture,
+/// ahead of the main pass, writing depth only (no fragment stage).
+struct TerrainDepthPrepassNode;
+
+impl TerrainDepthPrepassNode {
+    fn new() -> Self {
+        TerrainDepthPrepassNode
+    }
+}
+
+impl Node for TerrainDepthPrepassNode {
+    fn input(&self) -> Vec<SlotInfo> {
+        vec![SlotInfo::new("view", SlotType::Entity)]
+    }
+
+    fn run(
+        &self,
+        graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &bevy::prelude::World,
+    ) -> Result<(), NodeRunError> {
+        let (
+            Some(depth_prepass_texture),
+            Some(queued_meshes),
+            Some(gpu_meshes),
+            Some(meta),
+        ) = (
+            world.get_resource::<TerrainDepthPrepassTexture>(),
+            world.get_resource::<QueuedTerrainDepthMeshes>(),
+            world.get_resource::<RenderAssets<Mesh>>(),
+            world.get_resource::<TerrainDepthPrepassMeta>(),
+        )
+        else {
+            return Ok(());
+        };
+        let (Some(view_bind_group), Some(mesh_bind_group)) =
+            (&meta.view_bind_group, &meta.mesh_bind_group)
+        else {
+            return Ok(());
+        };
+
+        let view_entity = graph.get_input_entity("view")?;
+        let Some(view_uniform_offset) = world.get::<ViewUniformOffset>(view_entity) else {
+            return Ok(());
+        };
+        let pipeline_cache = world.resource::<PipelineCache>();
+
+        let mut render_pass =
+            render_context
+                .command_encoder
+                .begin_render_pass(&RenderPassDescriptor {
+                    label: Some("terrain_depth_prepass"),
+                    color_attachments: &[],
+                    depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                        view: &depth_prepass_texture.view,
+                        depth_ops: Some(Operations {
+                            load: LoadOp::Clear(1.0),
+                            store: true,
+                        }),
+                        stencil_ops: None,
+                    }),
+                });
+
+        for queued_mesh in &queued_meshes.meshes {
+            let Some(pipeline) = pipeline_cache.get_render_pipeline(queued_mesh.pipeline_id)
+            else {
+                continue;
+            };
+            let Some(gpu_mesh) = gpu_meshes.get(&queued_mesh.mesh) else {
+                continue;
+            };
+
+            render_pass.set_render_pipeline(pipeline);
+            render_pass.set_bind_group(0, view_bind_group, &[view_uniform_offset.offset]);
+            render_pass.set_bind_group(1, mesh_bind_group, &[queued_mesh.mesh_uniform_offset]);
+            render_pass.set_vertex_buffer(0, gpu_mesh.vertex_buffer.slice(..));
+            match &gpu_mesh.buffer_info {
+                GpuBufferInfo::Indexed {
+                    buffer,
+                    index_format,
+                    count,
+                } => {
+                    render_pass.set_index_buffer(buffer.slice(..), 0, *index_format);
+                    render_pass.draw_indexed(0..*count, 0, 0..1);
+                }
+                GpuBufferInfo::NonIndexed { vertex_count } => {
+                    render_pass.draw(0..*vertex_count, 0..1);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}